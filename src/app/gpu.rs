@@ -1,16 +1,77 @@
-use std::{borrow::Cow, future::Future, sync::Arc};
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
-use anyhow::{Context, Error, Result};
+use anyhow::{Context, Result};
+use bytemuck::{bytes_of, cast_slice, Pod, Zeroable};
+use image::GrayImage;
 use parking_lot::Mutex;
-use tracing::{instrument, warn};
+use tracing::{debug, instrument, warn};
 use wgpu::{
-    Adapter, Backends, CompositeAlphaMode, Device, DeviceDescriptor, ErrorFilter, Instance,
-    PresentMode, Queue, RequestAdapterOptions, ShaderModuleDescriptor, ShaderSource, Surface,
-    SurfaceConfiguration, TextureFormat, TextureUsages,
+    util::{BufferInitDescriptor, DeviceExt},
+    Adapter, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferDescriptor, BufferUsages,
+    Color, ColorTargetState, ColorWrites, CommandEncoderDescriptor, CompositeAlphaMode,
+    DeviceDescriptor, Extent3d, FilterMode, FragmentState, ImageCopyBuffer, ImageCopyTexture,
+    ImageDataLayout, Instance, LoadOp, Operations, PipelineLayoutDescriptor, PresentMode,
+    RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor,
+    SamplerBindingType, SamplerDescriptor, ShaderStages,
+    Surface, SurfaceConfiguration, Texture, TextureAspect, TextureDescriptor, TextureDimension,
+    TextureFormat, TextureSampleType, TextureUsages, TextureViewDescriptor, TextureViewDimension,
+    VertexState,
 };
 use winit::event_loop::EventLoopProxy;
 
 use crate::app::{ui::UiMessage, Ui};
+use crate::engine::{Binding, Engine, ShaderId};
+use crate::wgpu_api_shim;
+
+const WIDTH: u32 = 1024; // make sure as multiply of 256
+const HEIGHT: u32 = 1024;
+const POINT: u32 = 8;
+/// Source frequency in cycles per simulation time unit. The source term is
+/// sampled once per step at `DT` apart, so `FREQUENCY * DT` must stay well
+/// below the Nyquist limit of `0.5` or the sampled `sin` aliases into noise
+/// instead of a coherent wave; `FREQUENCY * DT == 0.1` here gives 10 steps
+/// per cycle.
+const FREQUENCY: f32 = 0.5;
+
+const STEPS_PER_FRAME: u32 = 4;
+const DT: f32 = 0.2;
+const SOURCE_AMPLITUDE: f32 = 0.6;
+/// `(wave speed * dt / dx)^2` with `dx` taken as one cell. CFL stability for the
+/// 2D scalar wave equation requires this to stay at or below 0.5.
+const C2: f32 = 0.04;
+
+#[repr(C, align(1))]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct FdtdScene {
+    time: f32,
+    dt: f32,
+    c2: f32,
+    freq: f32,
+    amplitude: f32,
+    count: u32,
+    width: u32,
+    height: u32,
+}
+
+/// The three ping-pong field buffers the FDTD step reads/writes, plus the
+/// simulation clock that advances by `DT` each step. Cloning is cheap: `Buffer`
+/// wraps a ref-counted handle, so this just hands out another reference to the
+/// same GPU allocations.
+#[derive(Clone)]
+struct WaveState {
+    u_prev: Buffer,
+    u_curr: Buffer,
+    u_next: Buffer,
+    time: f32,
+}
 
 pub struct Gpu {
     ui: Mutex<EventLoopProxy<UiMessage>>,
@@ -18,43 +79,31 @@ pub struct Gpu {
     surface: Surface,
     surface_config: SurfaceConfiguration,
     adapter: Adapter,
-    device: Device,
-    queue: Queue,
+    engine: Engine,
+    shader: Mutex<Option<ShaderId>>,
+    wave: Mutex<Option<WaveState>>,
+    computing: AtomicBool,
+    start: Instant,
+    field_texture: Texture,
+    render_pipeline: RenderPipeline,
+    field_bind_group: BindGroup,
 }
 
 impl Gpu {
     pub async fn new(ui: &Ui) -> Result<Arc<Self>> {
-        let instance = Instance::new(if cfg!(target_family = "wasm") {
-            Backends::BROWSER_WEBGPU
-        } else if cfg!(windows) {
-            Backends::from_iter([Backends::DX12, Backends::DX11])
-        } else if cfg!(target_vendor = "apple") {
-            Backends::METAL
-        } else if cfg!(target_os = "linux") {
-            Backends::VULKAN
-        } else {
-            Backends::all()
-        });
+        let instance = wgpu_api_shim::create_instance();
 
         let surface = unsafe { instance.create_surface(ui.get_window()) };
-        let adapter = instance
-            .request_adapter(&RequestAdapterOptions {
-                power_preference: Default::default(),
-                force_fallback_adapter: false,
-                compatible_surface: None,
-            })
-            .await
-            .ok_or_else(|| Error::msg("no adapter found"))?;
+        let adapter = wgpu_api_shim::request_adapter(&instance, None).await?;
 
-        let (device, queue) = adapter
-            .request_device(
-                &DeviceDescriptor {
-                    ..Default::default()
-                },
-                None,
-            )
-            .await
-            .context("request gpu device")?;
+        let (device, queue) = wgpu_api_shim::request_device(
+            &adapter,
+            &DeviceDescriptor {
+                ..Default::default()
+            },
+        )
+        .await?;
+        crate::device_error::install_uncaptured_handler(&device);
 
         let size = ui.get_window().inner_size();
         let surface_config = SurfaceConfiguration {
@@ -68,14 +117,112 @@ impl Gpu {
 
         surface.configure(&device, &surface_config);
 
+        let field_texture = device.create_texture(&TextureDescriptor {
+            label: Some("field:texture"),
+            size: Extent3d {
+                width: WIDTH,
+                height: HEIGHT,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R32Float,
+            usage: TextureUsages::COPY_DST | TextureUsages::COPY_SRC | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let field_view = field_texture.create_view(&TextureViewDescriptor::default());
+        // `r32float` is `unfilterable-float` unless the adapter opts into
+        // `Features::FLOAT32_FILTERABLE` (which we don't request), so the sampler
+        // and bind group layout below must match that: nearest filtering and an
+        // explicit non-filtering sampler binding instead of the default `Linear`
+        // + auto-derived layout, which would fail bind group validation.
+        let field_sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("field:sampler"),
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let render_shader =
+            wgpu_api_shim::create_shader(&device, "render.wgsl", include_str!("../shaders/render.wgsl"));
+        let field_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("render:bind:layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::NonFiltering),
+                    count: None,
+                },
+            ],
+        });
+        let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("render:pipe:layout"),
+            bind_group_layouts: &[&field_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("render:pipe"),
+            layout: Some(&render_pipeline_layout),
+            vertex: VertexState {
+                module: &render_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: Default::default(),
+            depth_stencil: None,
+            multisample: Default::default(),
+            fragment: Some(FragmentState {
+                module: &render_shader,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format: surface_config.format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        let field_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("render:bind"),
+            layout: &field_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&field_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&field_sampler),
+                },
+            ],
+        });
+
         Ok(Arc::new(Self {
             ui: Mutex::new(ui.create_proxy()),
             instance,
             surface,
             surface_config,
             adapter,
-            device,
-            queue,
+            engine: Engine::new(device, queue),
+            shader: Mutex::new(None),
+            wave: Mutex::new(None),
+            computing: AtomicBool::new(false),
+            start: Instant::now(),
+            field_texture,
+            render_pipeline,
+            field_bind_group,
         }))
     }
 
@@ -87,31 +234,258 @@ impl Gpu {
                 return;
             }
         };
+        let view = surface.texture.create_view(&TextureViewDescriptor::default());
+
+        let mut encoder = self
+            .engine
+            .device()
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("draw:encoder"),
+            });
+        {
+            let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("draw:pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            pass.set_pipeline(&self.render_pipeline);
+            pass.set_bind_group(0, &self.field_bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+        wgpu_api_shim::submit(self.engine.queue(), encoder);
 
         surface.present();
     }
 
+    /// Runs one compute + field upload cycle. Frames are re-triggered from
+    /// `RedrawRequested`; the `computing` flag drops an ignite that overlaps one
+    /// already in flight instead of piling up concurrent dispatches.
     pub fn ignite(self: Arc<Self>) -> impl 'static + Future<Output = ()> + Send {
         async move {
+            if self.computing.swap(true, Ordering::AcqRel) {
+                return;
+            }
+
             let result = self.compute().await;
+            self.computing.store(false, Ordering::Release);
             self.send_message(UiMessage::ComputeComplete(Arc::new(result)));
         }
     }
 
     #[instrument(skip_all)]
     async fn compute(self: &Arc<Self>) -> Result<()> {
-        let shader = self
+        let shader = self.shader_id().await?;
+        self.send_message(UiMessage::ProgressUpdate { stage: "stepping" });
+
+        let mut state = self.wave_state().await?;
+        for _ in 0..STEPS_PER_FRAME {
+            let scene = FdtdScene {
+                time: state.time,
+                dt: DT,
+                c2: C2,
+                freq: FREQUENCY,
+                amplitude: SOURCE_AMPLITUDE,
+                count: POINT,
+                width: WIDTH,
+                height: HEIGHT,
+            };
+            let scene_buf = self
+                .engine
+                .checked_device_op(async {
+                    self.engine.device().create_buffer_init(&BufferInitDescriptor {
+                        label: Some("fdtd:bind:scene"),
+                        contents: bytes_of(&scene),
+                        usage: BufferUsages::UNIFORM,
+                    })
+                })
+                .await
+                .context("create uniform scene buf")?;
+
+            self.engine
+                .dispatch(
+                    shader,
+                    &[
+                        Binding::Buffer(&scene_buf),
+                        Binding::Buffer(&state.u_prev),
+                        Binding::Buffer(&state.u_curr),
+                        Binding::Buffer(&state.u_next),
+                    ],
+                    (WIDTH, HEIGHT, 1),
+                )
+                .await
+                .context("dispatch fdtd step")?;
+
+            // rotate the ping-pong buffers: curr becomes prev, next becomes curr,
+            // and the old prev is reused as the target for the following step.
+            state = WaveState {
+                u_prev: state.u_curr,
+                u_curr: state.u_next,
+                u_next: state.u_prev,
+                time: state.time + DT,
+            };
+        }
+        debug!("fdtd steps submitted");
+        self.send_message(UiMessage::ProgressUpdate { stage: "uploading" });
+
+        let mut encoder = self
+            .engine
+            .checked_device_op(async {
+                self.engine.device().create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("compute:upload:encoder"),
+                })
+            })
+            .await
+            .context("create field upload encoder")?;
+        encoder.copy_buffer_to_texture(
+            ImageCopyBuffer {
+                buffer: &state.u_curr,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(WIDTH * 4),
+                    rows_per_image: Some(HEIGHT),
+                },
+            },
+            ImageCopyTexture {
+                texture: &self.field_texture,
+                mip_level: 0,
+                origin: Default::default(),
+                aspect: TextureAspect::All,
+            },
+            Extent3d {
+                width: WIDTH,
+                height: HEIGHT,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.engine
+            .checked_device_op(async { wgpu_api_shim::submit(self.engine.queue(), encoder) })
+            .await
+            .context("submit field upload")?;
+        debug!("field upload submitted");
+        self.engine.poll_until_idle().await;
+        self.send_message(UiMessage::ProgressUpdate { stage: "idle" });
+
+        *self.wave.lock() = Some(state);
+
+        Ok(())
+    }
+
+    /// Returns the current ping-pong field buffers, allocating and zero-initializing
+    /// them on first use. Persisted across frames so the simulation keeps evolving
+    /// instead of restarting from a flat field every dispatch.
+    async fn wave_state(&self) -> Result<WaveState> {
+        if let Some(state) = self.wave.lock().clone() {
+            return Ok(state);
+        }
+
+        let zero = vec![0u8; WIDTH as usize * HEIGHT as usize * 4];
+        let state = self
+            .engine
             .checked_device_op(async {
-                self.device.create_shader_module(ShaderModuleDescriptor {
-                    label: Some("compute.wgsl"),
-                    source: ShaderSource::Wgsl(Cow::Borrowed(include_str!(
-                        "../shaders/compute.wgsl"
-                    ))),
+                let device = self.engine.device();
+                let make = |label: &str| {
+                    device.create_buffer_init(&BufferInitDescriptor {
+                        label: Some(label),
+                        contents: &zero,
+                        usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+                    })
+                };
+                WaveState {
+                    u_prev: make("wave:u_prev"),
+                    u_curr: make("wave:u_curr"),
+                    u_next: make("wave:u_next"),
+                    time: 0.0,
+                }
+            })
+            .await
+            .context("create wave buffers")?;
+
+        *self.wave.lock() = Some(state.clone());
+        Ok(state)
+    }
+
+    /// Registers `fdtd.wgsl` with the engine on first use so later frames reuse
+    /// the same pipeline and bind group layout instead of rebuilding them.
+    async fn shader_id(&self) -> Result<ShaderId> {
+        if let Some(id) = *self.shader.lock() {
+            return Ok(id);
+        }
+
+        let id = self
+            .engine
+            .add_shader(include_str!("../shaders/fdtd.wgsl"), "step")
+            .await
+            .context("add fdtd shader")?;
+        *self.shader.lock() = Some(id);
+        Ok(id)
+    }
+
+    /// On-demand PNG export of the current field texture, triggered by
+    /// `UiMessage::Snapshot` instead of running automatically every frame.
+    pub async fn snapshot(&self) -> Result<()> {
+        let device = self.engine.device();
+
+        let stage = self
+            .engine
+            .checked_device_op(async {
+                device.create_buffer(&BufferDescriptor {
+                    label: Some("snapshot:stage"),
+                    size: (WIDTH * HEIGHT * 4) as u64,
+                    usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            })
+            .await
+            .context("create snapshot stage buffer")?;
+
+        let mut encoder = self
+            .engine
+            .checked_device_op(async {
+                device.create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("snapshot:encoder"),
                 })
             })
             .await
-            .context("create shader")?;
-        
+            .context("create snapshot encoder")?;
+        encoder.copy_texture_to_buffer(
+            self.field_texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &stage,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(WIDTH * 4),
+                    rows_per_image: Some(HEIGHT),
+                },
+            },
+            Extent3d {
+                width: WIDTH,
+                height: HEIGHT,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.engine
+            .checked_device_op(async { wgpu_api_shim::submit(self.engine.queue(), encoder) })
+            .await
+            .context("submit snapshot copy")?;
+
+        let data = wgpu_api_shim::readback(device, &stage)
+            .await
+            .context("map snapshot")?;
+
+        let pixels = cast_slice::<u8, f32>(&data)
+            .iter()
+            .map(|g| (255.0 * g.clamp(0.0, 1.0)) as u8)
+            .collect::<Vec<_>>();
+        let image = GrayImage::from_vec(WIDTH, HEIGHT, pixels).context("create snapshot image")?;
+        image.save("output.png").context("save snapshot")?;
+
         Ok(())
     }
 
@@ -120,16 +494,4 @@ impl Gpu {
             warn!("send ui message error {}", err);
         }
     }
-
-    async fn checked_device_op<F, R>(&self, fut: F) -> Result<R>
-    where
-        F: Future<Output = R>,
-    {
-        self.device.push_error_scope(ErrorFilter::Validation);
-        let r = fut.await;
-        if let Some(err) = self.device.pop_error_scope().await {
-            return Err(Error::msg(format!("device error {}", err)));
-        }
-        Ok(r)
-    }
 }
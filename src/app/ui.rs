@@ -5,7 +5,7 @@ use tokio::sync::RwLock;
 use tracing::{info, trace, warn};
 use winit::{
     dpi::LogicalSize,
-    event::{Event, WindowEvent},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{EventLoop, EventLoopBuilder, EventLoopProxy},
     window::{Window, WindowBuilder},
 };
@@ -15,7 +15,11 @@ use crate::app::Gpu;
 #[derive(Debug, Clone)]
 pub enum UiMessage {
     ComputeComplete(Arc<Result<()>>),
-    ProgressUpdate,
+    /// The current compute frame moved on to a named stage; `stage` is shown in
+    /// the window title so progress is visible even on a slow frame.
+    ProgressUpdate { stage: &'static str },
+    /// On-demand PNG export of the current frame, requested with the S key.
+    Snapshot,
 }
 
 pub struct Ui {
@@ -76,6 +80,19 @@ impl Ui {
                     WindowEvent::CloseRequested => {
                         flow.set_exit();
                     }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(VirtualKeyCode::S),
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    } => {
+                        if let Err(err) = self.create_proxy().send_event(UiMessage::Snapshot) {
+                            warn!("send snapshot request error {}", err);
+                        }
+                    }
                     _ => {}
                 },
                 Event::MainEventsCleared => {
@@ -84,12 +101,23 @@ impl Ui {
                 Event::RedrawRequested(..) => {
                     if let Some(gpu) = self.gpu.blocking_read().upgrade() {
                         gpu.draw();
+                        tokio::spawn(gpu.ignite());
                     }
                 }
                 Event::UserEvent(message) => match message {
-                    UiMessage::ProgressUpdate => {
+                    UiMessage::ProgressUpdate { stage } => {
+                        self.window.set_title(&format!("wave test — {}", stage));
                         self.window.request_redraw();
                     }
+                    UiMessage::Snapshot => {
+                        if let Some(gpu) = self.gpu.blocking_read().upgrade() {
+                            tokio::spawn(async move {
+                                if let Err(err) = gpu.snapshot().await {
+                                    warn!("snapshot error {}", err);
+                                }
+                            });
+                        }
+                    }
                     UiMessage::ComputeComplete(result) => match result.as_ref() {
                         Ok(_) => {
                             info!("refresh ui by complete");
@@ -0,0 +1,49 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+/// A classified `wgpu` device failure, keeping the original `wgpu::Error` as
+/// the `source` so `anyhow`'s `{:?}` prints the underlying driver message
+/// instead of the flattened `"... error {}"` string the error-scope call
+/// sites used to build by hand.
+#[derive(Debug)]
+pub enum DeviceError {
+    OutOfMemory(wgpu::Error),
+    Validation(wgpu::Error),
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::OutOfMemory(_) => write!(f, "gpu device out of memory"),
+            DeviceError::Validation(_) => write!(f, "gpu device validation error"),
+        }
+    }
+}
+
+impl StdError for DeviceError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            DeviceError::OutOfMemory(err) | DeviceError::Validation(err) => Some(err),
+        }
+    }
+}
+
+impl From<wgpu::Error> for DeviceError {
+    fn from(err: wgpu::Error) -> Self {
+        match &err {
+            wgpu::Error::OutOfMemory { .. } => DeviceError::OutOfMemory(err),
+            wgpu::Error::Validation { .. } => DeviceError::Validation(err),
+        }
+    }
+}
+
+/// Registers a `device.on_uncaptured_error` handler so GPU faults that land
+/// outside an explicit error scope (e.g. during async submission) are routed
+/// through the same [`DeviceError`] classification and logged, instead of
+/// panicking the driver thread.
+pub fn install_uncaptured_handler(device: &wgpu::Device) {
+    device.on_uncaptured_error(Box::new(|err| {
+        let err = anyhow::Error::new(DeviceError::from(err));
+        tracing::error!("uncaptured gpu error: {:?}", err);
+    }));
+}
@@ -0,0 +1,177 @@
+use std::future::Future;
+
+use anyhow::{Context, Error, Result};
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+use wgpu::{
+    BindGroupDescriptor, BindGroupEntry, BindGroupLayout, Buffer, CommandEncoderDescriptor,
+    ComputePassDescriptor, ComputePipeline, Device, ErrorFilter, Queue,
+};
+
+use crate::device_error::DeviceError;
+use crate::wgpu_api_shim;
+
+/// Handle to a shader registered with an [`Engine`]. Cheap to copy, only valid for
+/// the engine it was returned from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShaderId(usize);
+
+/// A single bind group resource supplied to [`Engine::dispatch`], in binding order.
+pub enum Binding<'a> {
+    Buffer(&'a Buffer),
+}
+
+struct ShaderEntry {
+    pipeline: ComputePipeline,
+    bind_group_layout: BindGroupLayout,
+}
+
+/// Owns compiled compute pipelines so callers can register a shader once and
+/// re-dispatch it cheaply across frames instead of rebuilding the shader module,
+/// pipeline and bind group layout on every call. The bind group is still built
+/// per dispatch from the caller's bindings.
+pub struct Engine {
+    device: Device,
+    queue: Queue,
+    shaders: Mutex<Vec<ShaderEntry>>,
+}
+
+impl Engine {
+    pub fn new(device: Device, queue: Queue) -> Self {
+        Self {
+            device,
+            queue,
+            shaders: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &Queue {
+        &self.queue
+    }
+
+    pub async fn add_shader(&self, wgsl: &str, entry_point: &str) -> Result<ShaderId> {
+        let shader = self
+            .checked_device_op(async {
+                wgpu_api_shim::create_shader(&self.device, "engine:shader", wgsl)
+            })
+            .await
+            .context("create shader")?;
+
+        let pipeline = self
+            .checked_device_op(async {
+                wgpu_api_shim::create_compute_pipeline(&self.device, "engine:pipe", &shader, entry_point)
+            })
+            .await
+            .context("create compute pipeline")?;
+
+        let bind_group_layout = self
+            .checked_device_op(async { pipeline.get_bind_group_layout(0) })
+            .await
+            .context("get bind group layout")?;
+
+        let mut shaders = self.shaders.lock();
+        shaders.push(ShaderEntry { pipeline, bind_group_layout });
+        Ok(ShaderId(shaders.len() - 1))
+    }
+
+    pub async fn dispatch(
+        &self,
+        shader: ShaderId,
+        bindings: &[Binding<'_>],
+        workgroups: (u32, u32, u32),
+    ) -> Result<()> {
+        let (pipeline, bind_group_layout) = {
+            let shaders = self.shaders.lock();
+            let entry = shaders
+                .get(shader.0)
+                .ok_or_else(|| Error::msg("unknown shader id"))?;
+            (entry.pipeline.clone(), entry.bind_group_layout.clone())
+        };
+
+        let entries = bindings
+            .iter()
+            .enumerate()
+            .map(|(binding, resource)| BindGroupEntry {
+                binding: binding as u32,
+                resource: match resource {
+                    Binding::Buffer(buf) => buf.as_entire_binding(),
+                },
+            })
+            .collect::<Vec<_>>();
+
+        let bind_group = self
+            .checked_device_op(async {
+                self.device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("engine:bind"),
+                    layout: &bind_group_layout,
+                    entries: &entries,
+                })
+            })
+            .await
+            .context("create bind group")?;
+
+        let mut encoder = self
+            .checked_device_op(async {
+                self.device.create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("engine:encoder"),
+                })
+            })
+            .await
+            .context("create command encoder")?;
+
+        {
+            let mut pass = self
+                .checked_device_op(async {
+                    encoder.begin_compute_pass(&ComputePassDescriptor {
+                        label: Some("engine:pass"),
+                        timestamp_writes: None,
+                    })
+                })
+                .await
+                .context("start compute pass")?;
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(workgroups.0, workgroups.1, workgroups.2);
+        }
+
+        self.checked_device_op(async { wgpu_api_shim::submit(&self.queue, encoder) })
+            .await
+            .context("submit dispatch")?;
+
+        Ok(())
+    }
+
+    /// Wraps a device op in out-of-memory and validation error scopes, the same pattern every GPU
+    /// call site in this crate uses, centralized here so registered shaders and
+    /// caller-supplied buffer/bind group ops share one error path.
+    pub async fn checked_device_op<F, R>(&self, fut: F) -> Result<R>
+    where
+        F: Future<Output = R>,
+    {
+        self.device.push_error_scope(ErrorFilter::OutOfMemory);
+        self.device.push_error_scope(ErrorFilter::Validation);
+        let r = fut.await;
+        let validation = self.device.pop_error_scope().await;
+        let oom = self.device.pop_error_scope().await;
+        if let Some(err) = oom.or(validation) {
+            return Err(DeviceError::from(err)).context("device error");
+        }
+        Ok(r)
+    }
+
+    /// Drives `device.poll(Maintain::Poll)` on a timer until the queue reports
+    /// idle, instead of blocking the caller's thread on `Maintain::Wait`.
+    pub async fn poll_until_idle(&self) {
+        wgpu_api_shim::poll_until_idle(&self.device).await
+    }
+
+    /// Same idea as [`Engine::poll_until_idle`], but for a `map_async` callback:
+    /// keeps polling until the oneshot it feeds resolves.
+    pub async fn poll_until_mapped<T>(&self, rx: oneshot::Receiver<T>) -> Result<T> {
+        wgpu_api_shim::poll_until_mapped(&self.device, rx).await
+    }
+}
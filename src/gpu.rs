@@ -1,34 +1,71 @@
-use std::borrow::Cow;
-
-use anyhow::{Context, Error, Result};
+use anyhow::{Context, Result};
 use bytemuck::{bytes_of, cast_slice, Pod, Zeroable};
 use image::GrayImage;
 use tokio::sync::oneshot;
-use tracing::{debug, error, info};
+use tracing::{debug, info};
+#[cfg(feature = "profile")]
+use tracing::warn;
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
-    Backends, BindGroupDescriptor, BindGroupEntry, Buffer, BufferDescriptor, BufferUsages,
-    CommandEncoderDescriptor, ComputePassDescriptor, ComputePipelineDescriptor, Device,
-    DeviceDescriptor, ErrorFilter, Instance, Maintain, MapMode, Queue, RequestAdapterOptions,
-    ShaderModuleDescriptor, ShaderSource,
+    BindGroupDescriptor, BindGroupEntry, Buffer, BufferDescriptor, BufferUsages,
+    CommandEncoderDescriptor, ComputePassDescriptor, Device, DeviceDescriptor, ErrorFilter, Queue,
 };
 
+/// Drives `device.poll(Maintain::Poll)` on a timer until the queue reports idle,
+/// instead of blocking the thread on `Maintain::Wait`. Assumes at most one
+/// submission is in flight, which holds for this crate's one-shot dispatch/readback
+/// pattern. Delegates to [`wgpu_api_shim`] so the windowed path (which polls via
+/// `Engine`) and this standalone path share one implementation.
+pub(crate) async fn poll_until_idle(device: &Device) {
+    wgpu_api_shim::poll_until_idle(device).await
+}
+
+/// Same idea as [`poll_until_idle`], but for a `map_async` callback: keeps polling
+/// until the oneshot it feeds resolves.
+pub(crate) async fn poll_until_mapped<T>(device: &Device, rx: oneshot::Receiver<T>) -> Result<T> {
+    wgpu_api_shim::poll_until_mapped(device, rx).await
+}
+
+#[cfg(feature = "profile")]
+use wgpu::Features;
+
+#[cfg(feature = "profile")]
+use crate::profiling::Profiler;
+
+use crate::device_error::DeviceError;
+use crate::wgpu_api_shim;
+
+/// Result of timing the compute / extraction passes, surfaced to the caller when
+/// the `profile` feature is enabled and the adapter supports timestamp queries.
+#[cfg(feature = "profile")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProfileReport {
+    pub compute_ms: Option<f32>,
+    pub extract_ms: Option<f32>,
+}
+
 macro_rules! checked_device_op {
     ($ctx:literal, $device:expr, $op:block) => {{
+        $device.push_error_scope(ErrorFilter::OutOfMemory);
         $device.push_error_scope(ErrorFilter::Validation);
         let r = $op;
-        match $device.pop_error_scope().await {
+        let validation = $device.pop_error_scope().await;
+        let oom = $device.pop_error_scope().await;
+        match oom.or(validation) {
             None => r,
-            Some(err) => return Err(Error::msg(format!(concat!($ctx, " error {}"), err))),
+            Some(err) => return Err(DeviceError::from(err)).context(concat!($ctx, " error")),
         }
     }};
 
     (sync $ctx:literal, $device:expr, $op:block) => {{
+        $device.push_error_scope(ErrorFilter::OutOfMemory);
         $device.push_error_scope(ErrorFilter::Validation);
         let r = $op;
-        match Handle::current().block_on($device.pop_error_scope()) {
+        let validation = Handle::current().block_on($device.pop_error_scope());
+        let oom = Handle::current().block_on($device.pop_error_scope());
+        match oom.or(validation) {
             None => r,
-            Some(err) => return Err(Error::msg(format!(concat!($ctx, " error {}"), err))),
+            Some(err) => return Err(DeviceError::from(err)).context(concat!($ctx, " error")),
         }
     }};
 }
@@ -49,26 +86,9 @@ const POINT: u32 = 8;
 const FREQUENCY: u32 = 43000;
 
 pub async fn run() -> Result<()> {
-    let instance = Instance::new(if cfg!(target_family = "wasm") {
-        Backends::BROWSER_WEBGPU
-    } else if cfg!(windows) {
-        Backends::DX12 | Backends::DX11
-    } else if cfg!(target_vendor = "apple") {
-        Backends::METAL
-    } else if cfg!(target_os = "linux") {
-        Backends::VULKAN
-    } else {
-        Backends::all()
-    });
+    let instance = wgpu_api_shim::create_instance();
 
-    let adapter = instance
-        .request_adapter(&RequestAdapterOptions {
-            power_preference: Default::default(),
-            force_fallback_adapter: false,
-            compatible_surface: None,
-        })
-        .await
-        .ok_or_else(|| Error::msg("no adapter found"))?;
+    let adapter = wgpu_api_shim::request_adapter(&instance, None).await?;
 
     let info = adapter.get_info();
     info!("adapter {}", info.name);
@@ -77,15 +97,38 @@ pub async fn run() -> Result<()> {
     info!("  backend {:?}", info.backend);
     info!("  driver info {:?}", info.driver_info);
 
-    let (device, queue) = adapter
-        .request_device(
-            &DeviceDescriptor {
-                ..Default::default()
+    #[cfg(feature = "profile")]
+    let profile_supported = adapter.features().contains(Features::TIMESTAMP_QUERY);
+    #[cfg(feature = "profile")]
+    if !profile_supported {
+        warn!("adapter does not support timestamp queries, profiling disabled");
+    }
+    // `extract_buf` times a plain buffer copy by writing a timestamp straight onto
+    // the encoder, outside any compute/render pass, which needs this feature in
+    // addition to `TIMESTAMP_QUERY` (that one only covers pass-scoped writes).
+    #[cfg(feature = "profile")]
+    let encoder_timestamps_supported =
+        profile_supported && adapter.features().contains(Features::TIMESTAMP_QUERY_INSIDE_ENCODERS);
+
+    let (device, queue) = wgpu_api_shim::request_device(
+        &adapter,
+        &DeviceDescriptor {
+            #[cfg(feature = "profile")]
+            features: if profile_supported {
+                Features::TIMESTAMP_QUERY
+                    | if encoder_timestamps_supported {
+                        Features::TIMESTAMP_QUERY_INSIDE_ENCODERS
+                    } else {
+                        Features::empty()
+                    }
+            } else {
+                Features::empty()
             },
-            None,
-        )
-        .await
-        .context("request gpu device")?;
+            ..Default::default()
+        },
+    )
+    .await?;
+    crate::device_error::install_uncaptured_handler(&device);
 
     let limits = device.limits();
     info!(
@@ -95,32 +138,38 @@ pub async fn run() -> Result<()> {
         limits.max_compute_workgroup_size_z
     );
 
-    let output_buf = compute(&device, &queue).await?;
+    let (output_buf, compute_ms) = compute(&device, &queue).await?;
     info!("compute done, start extraction.");
 
-    let image = extract_buf(&device, &queue, output_buf).await?;
+    let (image, extract_ms) = extract_buf(&device, &queue, output_buf).await?;
     info!("extraction done, save to file.");
 
+    #[cfg(feature = "profile")]
+    {
+        let report = ProfileReport { compute_ms, extract_ms };
+        info!("profile report: {:?}", report);
+    }
+    #[cfg(not(feature = "profile"))]
+    let _ = (compute_ms, extract_ms);
+
     image.save("output.png").context("save image")?;
 
     Ok(())
 }
 
-async fn compute(device: &Device, queue: &Queue) -> Result<Buffer> {
+async fn compute(device: &Device, queue: &Queue) -> Result<(Buffer, Option<f32>)> {
+    #[cfg(feature = "profile")]
+    let profiler = device
+        .features()
+        .contains(Features::TIMESTAMP_QUERY)
+        .then(|| Profiler::new(device, queue));
+
     let shader = checked_device_op!("create shader", device, {
-        device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("compute.wgsl"),
-            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("./shaders/compute.wgsl"))),
-        })
+        wgpu_api_shim::create_shader(device, "compute.wgsl", include_str!("./shaders/compute.wgsl"))
     });
 
     let compute_pipe = checked_device_op!("create compute pipeline", device, {
-        device.create_compute_pipeline(&ComputePipelineDescriptor {
-            label: Some("compute:step:pipe"),
-            layout: None,
-            module: &shader,
-            entry_point: "step",
-        })
+        wgpu_api_shim::create_compute_pipeline(device, "compute:step:pipe", &shader, "step")
     });
 
     let bind_group_layout = checked_device_op!("get bind group layout", device, {
@@ -171,9 +220,15 @@ async fn compute(device: &Device, queue: &Queue) -> Result<Buffer> {
         })
     });
 
+    #[cfg(feature = "profile")]
+    let timestamp_writes = profiler.as_ref().map(Profiler::timestamp_writes);
+    #[cfg(not(feature = "profile"))]
+    let timestamp_writes = None;
+
     let mut pass = checked_device_op!("start compute pass", device, {
         encoder.begin_compute_pass(&ComputePassDescriptor {
             label: Some("compute:step:pass"),
+            timestamp_writes,
         })
     });
     pass.set_pipeline(&compute_pipe);
@@ -181,19 +236,47 @@ async fn compute(device: &Device, queue: &Queue) -> Result<Buffer> {
     pass.dispatch_workgroups(WIDTH, HEIGHT, 1);
     drop(pass);
 
-    let index = checked_device_op!("submit compute", device, {
-        queue.submit([encoder.finish()])
-    });
-    checked_device_op!("wait compute done", device, {
-        device.poll(Maintain::WaitForSubmissionIndex(index));
+    #[cfg(feature = "profile")]
+    if let Some(profiler) = &profiler {
+        profiler.resolve(&mut encoder);
+    }
+
+    checked_device_op!("submit compute", device, {
+        wgpu_api_shim::submit(queue, encoder)
     });
+    debug!("compute submitted");
+    poll_until_idle(device).await;
+    debug!("compute dispatch done");
 
-    Ok(output_buf)
+    #[cfg(feature = "profile")]
+    let compute_ms = match &profiler {
+        Some(profiler) => match profiler.read_ms(device).await {
+            Ok(ms) => Some(ms),
+            Err(err) => {
+                warn!("read compute timestamps error {}", err);
+                None
+            }
+        },
+        None => None,
+    };
+    #[cfg(not(feature = "profile"))]
+    let compute_ms = None;
+
+    Ok((output_buf, compute_ms))
 }
 
-async fn extract_buf(device: &Device, queue: &Queue, buf: Buffer) -> Result<GrayImage> {
+async fn extract_buf(device: &Device, queue: &Queue, buf: Buffer) -> Result<(GrayImage, Option<f32>)> {
     debug_assert_eq!(buf.size(), WIDTH as u64 * HEIGHT as u64 * 4);
 
+    // Unlike `compute`'s pass-scoped timestamp writes, this function writes
+    // timestamps directly on the encoder around a bare buffer copy, which needs
+    // `TIMESTAMP_QUERY_INSIDE_ENCODERS` in addition to `TIMESTAMP_QUERY`.
+    #[cfg(feature = "profile")]
+    let profiler = device
+        .features()
+        .contains(Features::TIMESTAMP_QUERY | Features::TIMESTAMP_QUERY_INSIDE_ENCODERS)
+        .then(|| Profiler::new(device, queue));
+
     let mut encoder = checked_device_op!("create encoder", device, {
         device.create_command_encoder(&CommandEncoderDescriptor {
             label: Some("extract:encoder"),
@@ -208,33 +291,43 @@ async fn extract_buf(device: &Device, queue: &Queue, buf: Buffer) -> Result<Gray
             mapped_at_creation: false,
         })
     });
+
+    #[cfg(feature = "profile")]
+    if let Some(profiler) = &profiler {
+        profiler.write_timestamp(&mut encoder, 0);
+    }
     checked_device_op!("copy buffer", device, {
         encoder.copy_buffer_to_buffer(&buf, 0, &stage, 0, buf.size())
     });
+    #[cfg(feature = "profile")]
+    if let Some(profiler) = &profiler {
+        profiler.write_timestamp(&mut encoder, 1);
+        profiler.resolve(&mut encoder);
+    }
 
-    let index = checked_device_op!("submit extraction", device, {
-        queue.submit([encoder.finish()])
-    });
-    checked_device_op!("wait extraction", device, {
-        device.poll(Maintain::WaitForSubmissionIndex(index));
+    checked_device_op!("submit extraction", device, {
+        wgpu_api_shim::submit(queue, encoder)
     });
+    debug!("extraction submitted");
+    poll_until_idle(device).await;
     info!("extract copy done, read stage");
 
-    let data = {
-        let slice = stage.slice(..);
-        let (tx, rx) = oneshot::channel();
-        slice.map_async(MapMode::Read, move |r| {
-            debug!("stage mapped result {:?}", r);
-            if let Err(err) = tx.send(r) {
-                error!("can't dispatch map result {:?}", err);
+    #[cfg(feature = "profile")]
+    let extract_ms = match &profiler {
+        Some(profiler) => match profiler.read_ms(device).await {
+            Ok(ms) => Some(ms),
+            Err(err) => {
+                warn!("read extract timestamps error {}", err);
+                None
             }
-        });
-        device.poll(Maintain::Wait);
-        rx.await.context("wait map stage")?.context("map stage")?;
-        let data = slice.get_mapped_range().to_vec();
-        stage.unmap();
-        data
+        },
+        None => None,
     };
+    #[cfg(not(feature = "profile"))]
+    let extract_ms = None;
+
+    debug!("mapping stage buffer");
+    let data = wgpu_api_shim::readback(device, &stage).await.context("map stage")?;
 
     let data = cast_slice::<_, f32>(&data)
         .iter()
@@ -244,5 +337,6 @@ async fn extract_buf(device: &Device, queue: &Queue, buf: Buffer) -> Result<Gray
     debug!("image top pixel: {}", data[0]);
 
     debug!("create GaryImage");
-    GrayImage::from_vec(WIDTH, HEIGHT, data).context("create image")
+    let image = GrayImage::from_vec(WIDTH, HEIGHT, data).context("create image")?;
+    Ok((image, extract_ms))
 }
@@ -1,7 +1,17 @@
 use tracing::{error, info, subscriber::set_global_default, Level};
 use tracing_subscriber::fmt;
 
+mod device_error;
+mod engine;
 mod gpu;
+// Gated behind a `profile` feature that must be declared in Cargo.toml
+// (`profile = []`, wired to `wgpu/profiling` if timestamp queries ever need
+// extra validation layers). This tree ships without a manifest, so the
+// feature is never enabled here; a real checkout needs that declaration
+// added before `--features profile` does anything.
+#[cfg(feature = "profile")]
+mod profiling;
+mod wgpu_api_shim;
 
 #[tokio::main]
 async fn main() {
@@ -0,0 +1,91 @@
+use anyhow::Result;
+use bytemuck::cast_slice;
+use tracing::debug;
+use wgpu::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoder, ComputePassTimestampWrites, Device,
+    QuerySet, QuerySetDescriptor, QueryType, Queue,
+};
+
+use crate::wgpu_api_shim;
+
+const TICK_COUNT: u64 = 2;
+const TICK_SIZE: u64 = std::mem::size_of::<u64>() as u64;
+
+/// Times a begin/end pair of GPU timestamps around a single command submission.
+///
+/// Only meaningful within the submission it was resolved in: ticks from different
+/// queue submissions (or different adapters) aren't comparable.
+pub struct Profiler {
+    query_set: QuerySet,
+    resolve_buf: Buffer,
+    stage_buf: Buffer,
+    period_ns: f32,
+}
+
+impl Profiler {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        let query_set = device.create_query_set(&QuerySetDescriptor {
+            label: Some("profile:query_set"),
+            ty: QueryType::Timestamp,
+            count: TICK_COUNT as u32,
+        });
+        let resolve_buf = device.create_buffer(&BufferDescriptor {
+            label: Some("profile:resolve"),
+            size: TICK_COUNT * TICK_SIZE,
+            usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let stage_buf = device.create_buffer(&BufferDescriptor {
+            label: Some("profile:stage"),
+            size: TICK_COUNT * TICK_SIZE,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            query_set,
+            resolve_buf,
+            stage_buf,
+            period_ns: queue.get_timestamp_period(),
+        }
+    }
+
+    /// Begin/end write indices to attach to a `ComputePassDescriptor`.
+    pub fn timestamp_writes(&self) -> ComputePassTimestampWrites {
+        ComputePassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(1),
+        }
+    }
+
+    /// Write a single timestamp at `index` directly on the encoder (for passes that
+    /// aren't a compute/render pass, e.g. a plain buffer copy).
+    pub fn write_timestamp(&self, encoder: &mut CommandEncoder, index: u32) {
+        encoder.write_timestamp(&self.query_set, index);
+    }
+
+    /// Resolve the query set and stage it for readback. Must be called on the same
+    /// encoder that recorded the timestamp writes, before `finish()`.
+    pub fn resolve(&self, encoder: &mut CommandEncoder) {
+        encoder.resolve_query_set(&self.query_set, 0..TICK_COUNT as u32, &self.resolve_buf, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buf,
+            0,
+            &self.stage_buf,
+            0,
+            TICK_COUNT * TICK_SIZE,
+        );
+    }
+
+    /// Map the staged ticks and convert the begin/end delta to milliseconds. Caller
+    /// must have already submitted and polled the encoder `resolve` was run on.
+    pub async fn read_ms(&self, device: &Device) -> Result<f32> {
+        let data = wgpu_api_shim::readback(device, &self.stage_buf).await?;
+        let ticks = cast_slice::<u8, u64>(&data).to_vec();
+        debug!("profile ticks: {:?}", ticks);
+
+        let delta = ticks[1].saturating_sub(ticks[0]);
+        Ok(delta as f32 * self.period_ns / 1_000_000.0)
+    }
+}
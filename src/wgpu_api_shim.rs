@@ -0,0 +1,137 @@
+//! Seam between the rest of the crate and `wgpu`. The compute and UI code goes
+//! through here for device setup, shader/pipeline creation, submission and
+//! buffer readback, rather than importing those `wgpu` calls directly, so a
+//! future alternate backend only has to satisfy this module's surface
+//! instead of every call site.
+//!
+//! This deliberately stays narrow: buffer/texture/bind-group creation and
+//! encoder/pass recording are still plain `wgpu` at the call site, since
+//! those stay expressive enough to leave alone. What's wrapped is the part
+//! that's genuinely backend-specific policy or was duplicated verbatim across
+//! the standalone (`gpu::run`) and windowed (`app::Gpu::new`) paths: backend
+//! selection, instance/adapter/device acquisition, shader/compute-pipeline
+//! creation, submission, and the map-poll-read readback pattern.
+
+use std::borrow::Cow;
+use std::time::Duration;
+
+use anyhow::{Context, Error, Result};
+use tokio::sync::oneshot;
+use wgpu::{
+    Adapter, Backends, Buffer, CommandEncoder, ComputePipeline, ComputePipelineDescriptor,
+    Device, DeviceDescriptor, Instance, Maintain, MapMode, Queue, RequestAdapterOptions,
+    ShaderModule, ShaderModuleDescriptor, ShaderSource, Surface,
+};
+
+/// Picks which `wgpu` backend(s) to request an adapter from for the current
+/// platform.
+pub fn select_backends() -> Backends {
+    if cfg!(target_family = "wasm") {
+        Backends::BROWSER_WEBGPU
+    } else if cfg!(windows) {
+        Backends::DX12 | Backends::DX11
+    } else if cfg!(target_vendor = "apple") {
+        Backends::METAL
+    } else if cfg!(target_os = "linux") {
+        Backends::VULKAN
+    } else {
+        Backends::all()
+    }
+}
+
+/// Creates a `wgpu::Instance` using [`select_backends`]'s platform policy.
+pub fn create_instance() -> Instance {
+    Instance::new(select_backends())
+}
+
+/// Requests an adapter, optionally compatible with `surface`.
+pub async fn request_adapter(instance: &Instance, surface: Option<&Surface>) -> Result<Adapter> {
+    instance
+        .request_adapter(&RequestAdapterOptions {
+            power_preference: Default::default(),
+            force_fallback_adapter: false,
+            compatible_surface: surface,
+        })
+        .await
+        .ok_or_else(|| Error::msg("no adapter found"))
+}
+
+/// Requests a device/queue pair from `adapter` with the caller-supplied
+/// descriptor (features, limits, etc. stay the caller's decision).
+pub async fn request_device(
+    adapter: &Adapter,
+    descriptor: &DeviceDescriptor<'_>,
+) -> Result<(Device, Queue)> {
+    adapter
+        .request_device(descriptor, None)
+        .await
+        .context("request gpu device")
+}
+
+/// Compiles a WGSL shader module.
+pub fn create_shader(device: &Device, label: &str, wgsl: &str) -> ShaderModule {
+    device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(label),
+        source: ShaderSource::Wgsl(Cow::Borrowed(wgsl)),
+    })
+}
+
+/// Creates a compute pipeline with an auto-derived bind group layout.
+pub fn create_compute_pipeline(
+    device: &Device,
+    label: &str,
+    module: &ShaderModule,
+    entry_point: &str,
+) -> ComputePipeline {
+    device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some(label),
+        layout: None,
+        module,
+        entry_point,
+    })
+}
+
+/// Finishes `encoder` and submits it to `queue`.
+pub fn submit(queue: &Queue, encoder: CommandEncoder) {
+    queue.submit([encoder.finish()]);
+}
+
+/// Drives `device.poll(Maintain::Poll)` on a timer until the queue reports
+/// idle, instead of blocking the caller's thread on `Maintain::Wait`.
+pub async fn poll_until_idle(device: &Device) {
+    loop {
+        if device.poll(Maintain::Poll) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+}
+
+/// Same idea as [`poll_until_idle`], but for a `map_async` callback: keeps
+/// polling until the oneshot it feeds resolves.
+pub async fn poll_until_mapped<T>(device: &Device, rx: oneshot::Receiver<T>) -> Result<T> {
+    tokio::pin!(rx);
+    loop {
+        tokio::select! {
+            result = &mut rx => return result.context("map callback channel closed"),
+            _ = tokio::time::sleep(Duration::from_millis(1)) => {
+                device.poll(Maintain::Poll);
+            }
+        }
+    }
+}
+
+/// Maps `buffer` for reading, polls `device` until the map completes, copies
+/// the bytes out and unmaps. The common tail end of every readback in this
+/// crate (extraction, snapshot export, profiler tick readback).
+pub async fn readback(device: &Device, buffer: &Buffer) -> Result<Vec<u8>> {
+    let slice = buffer.slice(..);
+    let (tx, rx) = oneshot::channel();
+    slice.map_async(MapMode::Read, move |r| {
+        let _ = tx.send(r);
+    });
+    poll_until_mapped(device, rx).await?.context("map buffer")?;
+    let data = slice.get_mapped_range().to_vec();
+    buffer.unmap();
+    Ok(data)
+}